@@ -0,0 +1,110 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::types::InitMessage;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Pluggable verification for the connection-init handshake.
+///
+/// A deployment supplies its own implementation via [`AppState`](crate::AppState)
+/// so that the relay never has to know how tokens are minted — it only asks
+/// whether a given [`InitMessage`] is allowed to subscribe.
+pub trait Auth: Send + Sync {
+    /// Return `true` if the client presenting `init` may be subscribed.
+    fn verify(&self, init: &InitMessage) -> bool;
+}
+
+/// Accept every client. This is the default when no secret is configured and
+/// keeps the relay usable in trusted single-tenant deployments.
+pub struct AllowAll;
+
+impl Auth for AllowAll {
+    fn verify(&self, _init: &InitMessage) -> bool {
+        true
+    }
+}
+
+/// Compare the presented `access_token` against a fixed shared secret.
+pub struct SharedSecret {
+    secret: String,
+}
+
+impl SharedSecret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self { secret: secret.into() }
+    }
+}
+
+impl Auth for SharedSecret {
+    fn verify(&self, init: &InitMessage) -> bool {
+        constant_time_eq(init.access_token.as_bytes(), self.secret.as_bytes())
+    }
+}
+
+/// Verify a token of the form `<expiry_unix>.<hex_hmac>` where the HMAC is taken
+/// over `"<device_id>.<expiry_unix>"` with a server-held key. Tokens past their
+/// expiry are rejected, so a leaked token only grants access for a bounded
+/// window.
+pub struct HmacToken {
+    key: Vec<u8>,
+}
+
+impl HmacToken {
+    pub fn new(key: impl Into<Vec<u8>>) -> Self {
+        Self { key: key.into() }
+    }
+}
+
+impl Auth for HmacToken {
+    fn verify(&self, init: &InitMessage) -> bool {
+        let Some((expiry_str, sig_hex)) = init.access_token.split_once('.') else {
+            return false;
+        };
+        let Ok(expiry) = expiry_str.parse::<u64>() else {
+            return false;
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        if expiry < now {
+            return false;
+        }
+
+        let Ok(sig) = decode_hex(sig_hex) else {
+            return false;
+        };
+        let mut mac = match HmacSha256::new_from_slice(&self.key) {
+            Ok(mac) => mac,
+            Err(_) => return false,
+        };
+        mac.update(init.device_id.as_bytes());
+        mac.update(b".");
+        mac.update(expiry_str.as_bytes());
+        mac.verify_slice(&sig).is_ok()
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    let mut diff = 0u8;
+    for (x, y) in a.iter().zip(b.iter()) {
+        diff |= x ^ y;
+    }
+    diff == 0
+}
+
+fn decode_hex(s: &str) -> Result<Vec<u8>, ()> {
+    if s.len() % 2 != 0 {
+        return Err(());
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| ()))
+        .collect()
+}
@@ -0,0 +1,185 @@
+use std::env;
+
+use serde::{Deserialize, Serialize};
+
+use crate::types::{ConnectionId, MessageStatus};
+
+/// A single member of the cluster and the base URL of its internal transport.
+#[derive(Clone)]
+pub struct ClusterNode {
+    pub id: String,
+    pub url: String,
+}
+
+/// Read-only view of cluster membership plus the identity of the local node.
+///
+/// The owner of a `device_id` is chosen by rendezvous (highest-random-weight)
+/// hashing so that adding a node only remaps the device ids the new node wins,
+/// removing a node only remaps the device ids it owned, and every node computes
+/// the same owner without coordination.
+pub struct ClusterMetadata {
+    self_id: String,
+    nodes: Vec<ClusterNode>,
+}
+
+impl ClusterMetadata {
+    /// Build membership from the environment. `CLUSTER_NODES` is a comma list of
+    /// `id=url` pairs and `CLUSTER_SELF` names the local node; clustering stays
+    /// off unless both are present and `CLUSTER_SELF` is a known node.
+    pub fn from_env() -> Option<Self> {
+        let raw = env::var("CLUSTER_NODES").ok()?;
+        let self_id = env::var("CLUSTER_SELF").ok()?;
+        let nodes: Vec<ClusterNode> = raw
+            .split(',')
+            .filter_map(|entry| entry.split_once('='))
+            .map(|(id, url)| ClusterNode { id: id.trim().to_string(), url: url.trim().to_string() })
+            .collect();
+        if nodes.iter().any(|n| n.id == self_id) {
+            Some(Self { self_id, nodes })
+        } else {
+            None
+        }
+    }
+
+    /// The node that owns `device_id`, or `None` if membership is empty.
+    pub fn owner_of(&self, device_id: &str) -> Option<&ClusterNode> {
+        self.nodes
+            .iter()
+            .max_by_key(|node| rendezvous_weight(&node.id, device_id))
+    }
+
+    /// Whether the local node owns `device_id`. A single-node cluster (or an
+    /// unresolvable owner) falls back to local ownership.
+    pub fn owns(&self, device_id: &str) -> bool {
+        match self.owner_of(device_id) {
+            Some(owner) => owner.id == self.self_id,
+            None => true,
+        }
+    }
+
+    pub fn self_id(&self) -> &str {
+        &self.self_id
+    }
+
+    /// Base URL of the local node, used to tell an owner where to relay frames.
+    pub fn self_url(&self) -> Option<&str> {
+        self.nodes.iter().find(|n| n.id == self.self_id).map(|n| n.url.as_str())
+    }
+}
+
+fn rendezvous_weight(node_id: &str, device_id: &str) -> u64 {
+    // FNV-1a over "node_id\0device_id" — deterministic across nodes and std
+    // versions, unlike DefaultHasher's per-process seeding.
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in node_id.bytes().chain(std::iter::once(0)).chain(device_id.bytes()) {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// A frame relayed between nodes: `payload` should be delivered to the
+/// `to_master` side of `device_id` on the receiving node.
+#[derive(Serialize, Deserialize)]
+pub struct ClusterFrame {
+    pub device_id: String,
+    pub to_master: bool,
+    /// When set, deliver only to the matching connection on the target side.
+    #[serde(default)]
+    pub target: Option<ConnectionId>,
+    pub payload: String,
+}
+
+/// A remote-subscription notice: the node at `node_url` has (or no longer has)
+/// a subscriber on the `to_master` side of `device_id`, and wants the owner to
+/// relay matching frames to it.
+#[derive(Serialize, Deserialize)]
+pub struct ClusterSubscription {
+    pub device_id: String,
+    pub to_master: bool,
+    pub node_url: String,
+    pub subscribe: bool,
+}
+
+/// Internal node-to-node transport. Analogous to the local `subscribe_*`/
+/// broadcast path but carried over HTTP to the owning node.
+#[derive(Clone)]
+pub struct ClusterClient {
+    http: reqwest::Client,
+}
+
+impl ClusterClient {
+    pub fn new() -> Self {
+        // Bound per-request time so a hung or slow owner node cannot stall the
+        // sender's message loop, which awaits `forward` inline.
+        let http = reqwest::Client::builder()
+            .connect_timeout(std::time::Duration::from_secs(2))
+            .timeout(std::time::Duration::from_secs(5))
+            .build()
+            .unwrap_or_default();
+        Self { http }
+    }
+
+    /// Relay a frame to `base_url`'s internal forward endpoint and return the
+    /// delivery status the owner reported. Failures are returned so the caller
+    /// can treat an unreachable node as a dropped peer.
+    pub async fn forward(&self, base_url: &str, frame: &ClusterFrame) -> Result<MessageStatus, reqwest::Error> {
+        let status = self
+            .http
+            .post(format!("{}/internal/forward", base_url.trim_end_matches('/')))
+            .json(frame)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<MessageStatus>()
+            .await?;
+        Ok(status)
+    }
+
+    /// Register or clear a remote subscription with the owning node.
+    pub async fn subscribe(&self, base_url: &str, sub: &ClusterSubscription) -> Result<(), reqwest::Error> {
+        self.http
+            .post(format!("{}/internal/subscribe", base_url.trim_end_matches('/')))
+            .json(sub)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
+
+impl Default for ClusterClient {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `ClusterMetadata` is only constructible from the environment, which is
+    // process-global, so this single test sets it once and exercises ownership
+    // from both nodes' points of view. The inter-node HTTP forwarding path needs
+    // a multi-process harness and is not covered here.
+    #[test]
+    fn rendezvous_ownership_is_deterministic_and_agreed() {
+        std::env::set_var("CLUSTER_NODES", "a=http://a:8080,b=http://b:8080");
+
+        std::env::set_var("CLUSTER_SELF", "a");
+        let a = ClusterMetadata::from_env().expect("node a");
+        std::env::set_var("CLUSTER_SELF", "b");
+        let b = ClusterMetadata::from_env().expect("node b");
+
+        // Both nodes compute the same owner for any given device id.
+        for device in ["dev-1", "dev-2", "zzz", "a", "b"] {
+            let owner_a = a.owner_of(device).expect("owner").id.clone();
+            let owner_b = b.owner_of(device).expect("owner").id.clone();
+            assert_eq!(owner_a, owner_b, "nodes disagree on owner of {device}");
+            // Exactly one node considers itself the owner.
+            assert_eq!(a.owns(device), owner_a == "a");
+            assert_eq!(b.owns(device), owner_b == "b");
+            assert_ne!(a.owns(device), b.owns(device), "both/neither own {device}");
+        }
+    }
+}
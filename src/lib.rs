@@ -8,21 +8,47 @@ use tracing::info;
 use std::collections::HashMap;
 use std::sync::Arc;
 use std::env;
+use std::time::{Duration, Instant};
+use std::sync::atomic::{AtomicU64, Ordering};
 use tokio::sync::RwLock;
 
 mod types;
-use types::{ConnectParams, ClientType};
+use types::{ConnectParams, ClientType, InitMessage, Role, Envelope, MessageStatus, HistoryQuery, ConnectionId};
 
 mod state;
-use state::{MasterChannel, SlaveChannel, subscribe_master, subscribe_slave, log_forward};
+use state::{ConnectionPair, RelayFrame, QueueConfig, subscribe_master, subscribe_slave, drain_queue, log_forward,
+    add_remote_subscriber, remove_remote_subscriber};
+
+mod auth;
+use auth::{Auth, AllowAll, SharedSecret, HmacToken};
+
+mod storage;
+use storage::Storage;
+
+mod cluster;
+use cluster::{ClusterMetadata, ClusterClient, ClusterFrame, ClusterSubscription};
 
 type DeviceId = String;
-type ConnectionPair = (Option<MasterChannel>, Option<SlaveChannel>);
 type Connections = Arc<RwLock<HashMap<DeviceId, ConnectionPair>>>;
 
 #[derive(Clone)]
 struct AppState {
     connections: Connections,
+    auth: Arc<dyn Auth>,
+    queue_config: QueueConfig,
+    storage: Option<Arc<Storage>>,
+    cluster: Option<Arc<ClusterMetadata>>,
+    cluster_client: ClusterClient,
+    heartbeat: HeartbeatConfig,
+    next_conn_id: Arc<AtomicU64>,
+}
+
+/// Keepalive tuning: how often to Ping an idle connection and how long to wait
+/// for any traffic or Pong before evicting it.
+#[derive(Clone, Copy)]
+struct HeartbeatConfig {
+    interval: Duration,
+    timeout: Duration,
 }
 
 pub async fn run_server() {
@@ -35,11 +61,22 @@ pub async fn run_server_on(addr: &str) {
     let _ = tracing_subscriber::fmt::try_init();
 
     let connections: Connections = Arc::new(RwLock::new(HashMap::new()));
-    let state = AppState { connections: connections.clone() };
+    let state = AppState {
+        connections: connections.clone(),
+        auth: auth_from_env(),
+        queue_config: queue_config_from_env(),
+        storage: storage_from_env().await,
+        cluster: ClusterMetadata::from_env().map(Arc::new),
+        cluster_client: ClusterClient::new(),
+        heartbeat: heartbeat_from_env(),
+        next_conn_id: Arc::new(AtomicU64::new(1)),
+    };
 
     let app = Router::new()
         .route("/register", get(register_handler))
         .route("/pair", get(pair_handler))
+        .route("/internal/forward", axum::routing::post(internal_forward))
+        .route("/internal/subscribe", axum::routing::post(internal_subscribe))
         .with_state(state);
 
     info!("WebSocket server starting on {}", addr);
@@ -47,6 +84,55 @@ pub async fn run_server_on(addr: &str) {
     axum::serve(listener, app).await.unwrap();
 }
 
+/// Build the token verifier from the environment. `AUTH_SHARED_SECRET` selects
+/// a fixed shared secret, `AUTH_HMAC_SECRET` selects the expiring HMAC scheme,
+/// and when neither is set every client is accepted.
+fn auth_from_env() -> Arc<dyn Auth> {
+    if let Ok(secret) = env::var("AUTH_SHARED_SECRET") {
+        Arc::new(SharedSecret::new(secret))
+    } else if let Ok(key) = env::var("AUTH_HMAC_SECRET") {
+        Arc::new(HmacToken::new(key.into_bytes()))
+    } else {
+        Arc::new(AllowAll)
+    }
+}
+
+/// Read the store-and-forward queue bounds from the environment. `QUEUE_MAX_LEN`
+/// caps the number of buffered messages per side (default 100) and
+/// `QUEUE_TTL_SECS` bounds how long a buffered message is retained (default 300).
+fn queue_config_from_env() -> QueueConfig {
+    let max_len = env::var("QUEUE_MAX_LEN").ok().and_then(|v| v.parse().ok()).unwrap_or(100);
+    let ttl_secs = env::var("QUEUE_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(300);
+    QueueConfig { max_len, ttl: std::time::Duration::from_secs(ttl_secs) }
+}
+
+/// Open the persistence layer when `HISTORY_DB` points at a SQLite database.
+/// Persistence is off by default; a connection failure is logged and disables
+/// history rather than taking down the relay.
+async fn storage_from_env() -> Option<Arc<Storage>> {
+    let url = env::var("HISTORY_DB").ok()?;
+    match Storage::connect(&url).await {
+        Ok(storage) => Some(Arc::new(storage)),
+        Err(err) => {
+            tracing::warn!("history persistence disabled: {}", err);
+            None
+        }
+    }
+}
+
+/// Read keepalive tuning from the environment. `HEARTBEAT_INTERVAL_SECS`
+/// (default 30) sets the Ping cadence and `HEARTBEAT_TIMEOUT_SECS` (default 90)
+/// the idle window after which a silent peer is evicted. The interval is clamped
+/// to at least one second so the ticker never panics.
+fn heartbeat_from_env() -> HeartbeatConfig {
+    let interval = env::var("HEARTBEAT_INTERVAL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(30).max(1);
+    let timeout = env::var("HEARTBEAT_TIMEOUT_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(90);
+    HeartbeatConfig {
+        interval: Duration::from_secs(interval),
+        timeout: Duration::from_secs(timeout),
+    }
+}
+
 async fn register_handler(
     ws: WebSocketUpgrade,
     Query(params): Query<ConnectParams>,
@@ -69,21 +155,66 @@ async fn handle_connection(mut socket: WebSocket, device_id: DeviceId, client_ty
     let is_master = matches!(client_type, ClientType::Master);
     let name = client_type.to_string();
 
+    let init = match authenticate(&mut socket, &state, &device_id, &client_type).await {
+        Some(init) => init,
+        None => return,
+    };
+
+    let conn_id = state.next_conn_id.fetch_add(1, Ordering::Relaxed);
+
     if is_master {
-        let mut rx = subscribe_master(&state.connections, &device_id).await;
-        info!("{} {} connected", name, device_id);
+        let mut rx = subscribe_master(&state.connections, &device_id, conn_id, state.queue_config).await;
+        register_remote(&state, &device_id, true, true).await;
+        announce_peer(&state.connections, &device_id, true, conn_id, true).await;
+        info!("{} {} connected (conn {})", name, device_id, conn_id);
+
+        // A replay failure means the socket is already gone; fall through to the
+        // shared teardown below rather than returning early, so the cluster
+        // subscription and peer announcement are cleaned up like any other exit.
+        let replay_ok = !init.replay
+            || replay_buffered(&mut socket, &state.connections, &device_id, true).await.is_ok();
+
+        let mut last_activity = Instant::now();
+        let mut ticker = tokio::time::interval(state.heartbeat.interval);
+        ticker.tick().await; // consume the immediate first tick
 
-        loop {
+        while replay_ok {
             tokio::select! {
-                Ok(msg) = rx.recv() => {
-                    if socket.send(axum::extract::ws::Message::Text(msg.into())).await.is_err() {
+                Ok(frame) = rx.recv() => {
+                    if frame.target.map_or(true, |t| t == conn_id)
+                        && socket.send(axum::extract::ws::Message::Text(frame.text.into())).await.is_err()
+                    {
+                        break;
+                    }
+                }
+                _ = ticker.tick() => {
+                    if last_activity.elapsed() >= state.heartbeat.timeout {
+                        info!("{} {} idle past heartbeat timeout, evicting", name, device_id);
+                        break;
+                    }
+                    if socket.send(axum::extract::ws::Message::Ping(Vec::new().into())).await.is_err() {
                         break;
                     }
                 }
                 msg = socket.recv() => {
                     match msg {
                         Some(Ok(axum::extract::ws::Message::Text(text))) => {
-                            forward_message(&state.connections, &device_id, text.to_string(), true).await;
+                            last_activity = Instant::now();
+                            if let Some(query) = parse_history(&text) {
+                                if send_history(&mut socket, &state, &device_id, query).await.is_err() {
+                                    break;
+                                }
+                                continue;
+                            }
+                            if let Some((id, status)) = forward_message(&state, &device_id, text.to_string(), true).await {
+                                if send_status(&mut socket, &id, status).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        Some(Ok(axum::extract::ws::Message::Pong(_)))
+                        | Some(Ok(axum::extract::ws::Message::Ping(_))) => {
+                            last_activity = Instant::now();
                         }
                         _ => break,
                     }
@@ -91,20 +222,51 @@ async fn handle_connection(mut socket: WebSocket, device_id: DeviceId, client_ty
             }
         }
     } else {
-        let mut rx = subscribe_slave(&state.connections, &device_id).await;
-        info!("{} {} connected", name, device_id);
+        let mut rx = subscribe_slave(&state.connections, &device_id, conn_id, state.queue_config).await;
+        register_remote(&state, &device_id, false, true).await;
+        announce_peer(&state.connections, &device_id, false, conn_id, true).await;
+        info!("{} {} connected (conn {})", name, device_id, conn_id);
+
+        // As on the master side, a replay failure falls through to the shared
+        // teardown below instead of returning early.
+        let replay_ok = !init.replay
+            || replay_buffered(&mut socket, &state.connections, &device_id, false).await.is_ok();
 
-        loop {
+        let mut last_activity = Instant::now();
+        let mut ticker = tokio::time::interval(state.heartbeat.interval);
+        ticker.tick().await; // consume the immediate first tick
+
+        while replay_ok {
             tokio::select! {
-                Ok(msg) = rx.recv() => {
-                    if socket.send(axum::extract::ws::Message::Text(msg.into())).await.is_err() {
+                Ok(frame) = rx.recv() => {
+                    if frame.target.map_or(true, |t| t == conn_id)
+                        && socket.send(axum::extract::ws::Message::Text(frame.text.into())).await.is_err()
+                    {
+                        break;
+                    }
+                }
+                _ = ticker.tick() => {
+                    if last_activity.elapsed() >= state.heartbeat.timeout {
+                        info!("{} {} idle past heartbeat timeout, evicting", name, device_id);
+                        break;
+                    }
+                    if socket.send(axum::extract::ws::Message::Ping(Vec::new().into())).await.is_err() {
                         break;
                     }
                 }
                 msg = socket.recv() => {
                     match msg {
                         Some(Ok(axum::extract::ws::Message::Text(text))) => {
-                            forward_message(&state.connections, &device_id, text.to_string(), false).await;
+                            last_activity = Instant::now();
+                            if let Some((id, status)) = forward_message(&state, &device_id, text.to_string(), false).await {
+                                if send_status(&mut socket, &id, status).await.is_err() {
+                                    break;
+                                }
+                            }
+                        }
+                        Some(Ok(axum::extract::ws::Message::Pong(_)))
+                        | Some(Ok(axum::extract::ws::Message::Ping(_))) => {
+                            last_activity = Instant::now();
                         }
                         _ => break,
                     }
@@ -113,49 +275,385 @@ async fn handle_connection(mut socket: WebSocket, device_id: DeviceId, client_ty
         }
     }
 
-    unregister_client(&state.connections, &device_id, is_master).await;
-    info!("{} {} disconnected", name, device_id);
+    let _ = socket.send(axum::extract::ws::Message::Close(None)).await;
+    unregister_client(&state.connections, &device_id, is_master, conn_id).await;
+    register_remote(&state, &device_id, is_master, false).await;
+    announce_peer(&state.connections, &device_id, is_master, conn_id, false).await;
+    info!("{} {} disconnected (conn {})", name, device_id, conn_id);
 }
 
-async fn forward_message(connections: &Connections, device_id: &str, text: String, is_master: bool) {
-    let conn = connections.read().await;
-    if is_master {
-        if let Some((_master_opt, slave_opt)) = conn.get(device_id) {
-            if let Some(slv_chan) = slave_opt {
-                log_forward(device_id, "Master -> Slave", &text);
-                let _ = slv_chan.tx.send(text);
-            }
+/// Run the connection-init handshake: read the first text frame, validate it as
+/// an [`InitMessage`] for the expected role and device, and check it against the
+/// configured verifier. On any failure the socket is closed and `None` is
+/// returned, so the caller never subscribes an unauthenticated peer.
+async fn authenticate(
+    socket: &mut WebSocket,
+    state: &AppState,
+    device_id: &str,
+    client_type: &ClientType,
+) -> Option<InitMessage> {
+    use axum::extract::ws::{CloseFrame, Message};
+
+    let reject = |socket: &mut WebSocket, reason: &'static str| {
+        let frame = Message::Close(Some(CloseFrame { code: 1008, reason: reason.into() }));
+        async move {
+            let _ = socket.send(frame).await;
         }
-    } else {
-        if let Some((master_opt, _slave_opt)) = conn.get(device_id) {
-            if let Some(master_chan) = master_opt {
-                log_forward(device_id, "Slave -> Master", &text);
-                let _ = master_chan.tx.send(text);
+    };
+
+    let text = match socket.recv().await {
+        Some(Ok(Message::Text(text))) => text,
+        _ => {
+            reject(socket, "init frame required").await;
+            return None;
+        }
+    };
+
+    let init: InitMessage = match serde_json::from_str(&text) {
+        Ok(init) => init,
+        Err(_) => {
+            reject(socket, "malformed init frame").await;
+            return None;
+        }
+    };
+
+    let expected_role = match client_type {
+        ClientType::Master => Role::Master,
+        ClientType::Slave => Role::Slave,
+    };
+    if init.msg_type != "init" || init.role != expected_role {
+        reject(socket, "unexpected init role").await;
+        return None;
+    }
+
+    // The authenticated device must match the one the channel is keyed on, or a
+    // client could present a valid token for its own device while subscribing to
+    // a victim's pairing via `?id=<victim>`.
+    if init.device_id != device_id {
+        reject(socket, "device id mismatch").await;
+        return None;
+    }
+
+    if !state.auth.verify(&init) {
+        reject(socket, "authentication failed").await;
+        return None;
+    }
+
+    Some(init)
+}
+
+/// Drain messages buffered for the side that just attached and push them to its
+/// socket in order, prefixed with a `replay` notice carrying the buffered count.
+/// Returns `Err` if the socket drops mid-replay so the caller can tear down.
+async fn replay_buffered(
+    socket: &mut WebSocket,
+    connections: &Connections,
+    device_id: &str,
+    is_master: bool,
+) -> Result<(), ()> {
+    use axum::extract::ws::Message;
+
+    let buffered = drain_queue(connections, device_id, is_master).await;
+    let notice = format!("{{\"type\":\"replay\",\"count\":{}}}", buffered.len());
+    socket.send(Message::Text(notice.into())).await.map_err(|_| ())?;
+    for text in buffered {
+        socket.send(Message::Text(text.into())).await.map_err(|_| ())?;
+    }
+    Ok(())
+}
+
+/// Recognise a `{"type":"history",...}` control frame and parse it into a
+/// [`HistoryQuery`]. Any other frame (including ordinary JSON payloads) returns
+/// `None` and is forwarded normally.
+fn parse_history(text: &str) -> Option<HistoryQuery> {
+    let value: serde_json::Value = serde_json::from_str(text).ok()?;
+    if value.get("type").and_then(|t| t.as_str()) != Some("history") {
+        return None;
+    }
+    serde_json::from_value(value).ok()
+}
+
+/// Read the requested slice of persisted history and stream it back to the
+/// master as a single batch frame before live forwarding resumes. When
+/// persistence is disabled an empty batch is returned.
+///
+/// The read is always scoped to the connection's authenticated `device_id`; a
+/// `query.device_id` naming another device is ignored, so a master cannot read a
+/// peer's history the way the init check at [`authenticate`] already prevents.
+async fn send_history(socket: &mut WebSocket, state: &AppState, device_id: &str, query: HistoryQuery) -> Result<(), ()> {
+    let limit = query.limit.unwrap_or(100).clamp(0, 1000);
+    let entries = match &state.storage {
+        Some(storage) => storage
+            .history(device_id, limit, query.before_seq)
+            .await
+            .unwrap_or_default(),
+        None => Vec::new(),
+    };
+
+    let batch = serde_json::json!({
+        "type": "history",
+        "device_id": device_id,
+        "count": entries.len(),
+        "messages": entries,
+    });
+    let text = serde_json::to_string(&batch).unwrap_or_else(|_| "{\"type\":\"history\",\"messages\":[]}".to_string());
+    socket
+        .send(axum::extract::ws::Message::Text(text.into()))
+        .await
+        .map_err(|_| ())
+}
+
+/// Send a delivery-status acknowledgement for `id` back to the originating
+/// socket.
+async fn send_status(socket: &mut WebSocket, id: &str, status: MessageStatus) -> Result<(), ()> {
+    let ack = format!("{{\"type\":\"status\",\"id\":\"{}\",\"status\":\"{}\"}}", id, status.as_str());
+    socket
+        .send(axum::extract::ws::Message::Text(ack.into()))
+        .await
+        .map_err(|_| ())
+}
+
+/// Forward `text` to the counterpart side, buffering it when that side is
+/// offline. When the sender wrapped the payload in an [`Envelope`] this returns
+/// the message id together with the [`MessageStatus`] so the caller can
+/// acknowledge delivery; an unwrapped payload is forwarded fire-and-forget and
+/// yields `None`.
+async fn forward_message(
+    state: &AppState,
+    device_id: &str,
+    text: String,
+    is_master: bool,
+) -> Option<(String, MessageStatus)> {
+    // Unwrap an addressed envelope; a bare payload is forwarded verbatim to the
+    // whole counterpart side.
+    let (id, target, payload) = match serde_json::from_str::<Envelope>(&text) {
+        Ok(env) => {
+            let payload = serde_json::to_string(&env.payload).unwrap_or_else(|_| text.clone());
+            (env.id, env.to, payload)
+        }
+        Err(_) => (None, None, text),
+    };
+
+    // A master sends toward the slave side and vice versa.
+    let to_master = !is_master;
+    let direction = if is_master { "Master -> Slave" } else { "Slave -> Master" };
+
+    let status = route_to_target(state, device_id, to_master, target, payload.clone()).await;
+
+    // Persist outside the connections lock so a slow write never blocks relaying.
+    if let Some(storage) = &state.storage {
+        if let Err(err) = storage.record(device_id, direction, &payload).await {
+            tracing::warn!("failed to persist message for {}: {}", device_id, err);
+        }
+    }
+
+    id.map(|id| (id, status))
+}
+
+/// Route a payload to the `to_master` side of `device_id`. When the local node
+/// does not own the device the frame is relayed to the owning node; otherwise it
+/// is delivered locally (see [`deliver_target`]).
+async fn route_to_target(
+    state: &AppState,
+    device_id: &str,
+    to_master: bool,
+    target: Option<ConnectionId>,
+    payload: String,
+) -> MessageStatus {
+    if let Some(cluster) = &state.cluster {
+        if !cluster.owns(device_id) {
+            return match cluster.owner_of(device_id) {
+                Some(owner) => {
+                    let frame = ClusterFrame {
+                        device_id: device_id.to_string(),
+                        to_master,
+                        target,
+                        payload,
+                    };
+                    match state.cluster_client.forward(&owner.url, &frame).await {
+                        Ok(status) => status,
+                        Err(err) => {
+                            // Owner unreachable — treat as a departed node.
+                            tracing::warn!("cluster forward to {} failed: {}", owner.url, err);
+                            MessageStatus::NoRecipient
+                        }
+                    }
+                }
+                None => MessageStatus::NoRecipient,
+            };
+        }
+    }
+
+    deliver_target(state, device_id, to_master, target, payload).await
+}
+
+/// Deliver a payload to the `to_master` side on this (owning) node: push it into
+/// the local broadcast channel, fan it out to any remote nodes that subscribed
+/// on that side, and buffer it for replay when there is nowhere to deliver.
+///
+/// Fan-out is single-hop: only the owner holds remote subscriptions, and a node
+/// reached via [`internal_forward`] has none of its own, so a relayed frame is
+/// never re-relayed and no origin echo-suppression is needed. The owner does
+/// forward back to the node a frame came from when that node also hosts the
+/// counterpart side, which is the correct delivery — not an echo.
+async fn deliver_target(
+    state: &AppState,
+    device_id: &str,
+    to_master: bool,
+    target: Option<ConnectionId>,
+    payload: String,
+) -> MessageStatus {
+    let direction = if to_master { "Slave -> Master" } else { "Master -> Slave" };
+
+    let (delivered_local, remotes, buffered) = {
+        let mut conn = state.connections.write().await;
+        let entry = conn.entry(device_id.to_string()).or_insert_with(|| ConnectionPair::new(state.queue_config));
+        let delivered_local = {
+            let chan = if to_master { entry.master.as_ref() } else { entry.slave.as_ref() };
+            match chan {
+                // A targeted frame only counts as delivered if the addressed
+                // connection is actually subscribed here; otherwise every
+                // receiver filters it out and it must be buffered instead.
+                Some(chan) if target.map_or(true, |t| chan.subscribers.contains(&t)) => {
+                    log_forward(device_id, direction, &payload);
+                    let _ = chan.tx.send(RelayFrame { target, text: payload.clone() });
+                    true
+                }
+                _ => false,
             }
+        };
+        let remotes = entry.remotes(to_master);
+        let buffered = if !delivered_local && remotes.is_empty() {
+            let queue = if to_master { &mut entry.master_queue } else { &mut entry.slave_queue };
+            queue.push(payload.clone())
+        } else {
+            false
+        };
+        (delivered_local, remotes, buffered)
+    };
+
+    // Relay to the nodes hosting the counterpart's connections.
+    for url in &remotes {
+        let frame = ClusterFrame {
+            device_id: device_id.to_string(),
+            to_master,
+            target,
+            payload: payload.clone(),
+        };
+        if let Err(err) = state.cluster_client.forward(url, &frame).await {
+            tracing::warn!("cluster fan-out to {} failed: {}", url, err);
         }
     }
+
+    if delivered_local || !remotes.is_empty() {
+        MessageStatus::Delivered
+    } else {
+        buffer_status(buffered)
+    }
+}
+
+/// Register (or clear) this node's interest in a device side with the owning
+/// node, so the owner relays the counterpart's frames back to us. No-op when
+/// clustering is off or this node already owns the device.
+async fn register_remote(state: &AppState, device_id: &str, is_master: bool, subscribe: bool) {
+    let Some(cluster) = &state.cluster else {
+        return;
+    };
+    if cluster.owns(device_id) {
+        return;
+    }
+    let (Some(owner), Some(self_url)) = (cluster.owner_of(device_id), cluster.self_url()) else {
+        return;
+    };
+    let sub = ClusterSubscription {
+        device_id: device_id.to_string(),
+        to_master: is_master,
+        node_url: self_url.to_string(),
+        subscribe,
+    };
+    if let Err(err) = state.cluster_client.subscribe(&owner.url, &sub).await {
+        tracing::warn!("cluster subscribe to {} failed: {}", owner.url, err);
+    }
+}
+
+/// Internal endpoint: relay a frame received from a peer node into the local
+/// delivery path. The owner uses this to fan out to remote subscribers; a
+/// non-owner uses it to receive frames for its locally-connected counterpart.
+async fn internal_forward(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::Json(frame): axum::extract::Json<ClusterFrame>,
+) -> axum::Json<MessageStatus> {
+    let status = deliver_target(&state, &frame.device_id, frame.to_master, frame.target, frame.payload).await;
+    axum::Json(status)
+}
+
+/// Internal endpoint: record or clear a peer node's remote subscription.
+async fn internal_subscribe(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    axum::extract::Json(sub): axum::extract::Json<ClusterSubscription>,
+) -> axum::http::StatusCode {
+    if sub.subscribe {
+        add_remote_subscriber(&state.connections, &sub.device_id, sub.to_master, sub.node_url, state.queue_config).await;
+    } else {
+        remove_remote_subscriber(&state.connections, &sub.device_id, sub.to_master, &sub.node_url).await;
+    }
+    axum::http::StatusCode::OK
+}
+
+/// Map the result of buffering a message to the status reported to the sender.
+fn buffer_status(buffered: bool) -> MessageStatus {
+    if buffered {
+        MessageStatus::Buffered
+    } else {
+        MessageStatus::NoRecipient
+    }
 }
 
-async fn unregister_client(connections: &Connections, device_id: &str, is_master: bool) {
+async fn unregister_client(connections: &Connections, device_id: &str, is_master: bool, conn_id: ConnectionId) {
     let mut conn = connections.write().await;
     if let Some(entry) = conn.get_mut(device_id) {
         if is_master {
-            if let Some(chan) = &mut entry.0 {
-                chan.subscribers = chan.subscribers.saturating_sub(1);
-                if chan.subscribers == 0 {
-                    entry.0 = None;
+            if let Some(chan) = &mut entry.master {
+                chan.subscribers.remove(&conn_id);
+                if chan.subscribers.is_empty() {
+                    entry.master = None;
                 }
             }
-        } else {
-            if let Some(chan) = &mut entry.1 {
-                chan.subscribers = chan.subscribers.saturating_sub(1);
-                if chan.subscribers == 0 {
-                    entry.1 = None;
-                }
+        } else if let Some(chan) = &mut entry.slave {
+            chan.subscribers.remove(&conn_id);
+            if chan.subscribers.is_empty() {
+                entry.slave = None;
             }
         }
-        if entry.0.is_none() && entry.1.is_none() {
+        if entry.master.is_none()
+            && entry.slave.is_none()
+            && entry.master_remotes.is_empty()
+            && entry.slave_remotes.is_empty()
+        {
             conn.remove(device_id);
         }
     }
+}
+
+/// Announce a connection join or leave to the counterpart side as a
+/// `{"type":"peer_connected"|"peer_removed","conn_id":N}` notice, so peers can
+/// learn which connection ids they may target. Delivered via the counterpart's
+/// broadcast channel and silently skipped when that side is offline.
+async fn announce_peer(
+    connections: &Connections,
+    device_id: &str,
+    joiner_is_master: bool,
+    conn_id: ConnectionId,
+    connected: bool,
+) {
+    let kind = if connected { "peer_connected" } else { "peer_removed" };
+    let notice = format!("{{\"type\":\"{}\",\"conn_id\":{}}}", kind, conn_id);
+    let conn = connections.read().await;
+    if let Some(entry) = conn.get(device_id) {
+        // The counterpart of a master is the slave side, and vice versa.
+        let counterpart = if joiner_is_master { &entry.slave } else { &entry.master };
+        if let Some(chan) = counterpart {
+            let _ = chan.tx.send(RelayFrame { target: None, text: notice });
+        }
+    }
 }
\ No newline at end of file
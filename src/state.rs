@@ -1,43 +1,211 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
 use serde_json::Value as JsonValue;
 use tokio::sync::broadcast;
 use tracing::info;
 
+use crate::types::ConnectionId;
+
+/// A frame placed on a side's broadcast channel. `target` restricts delivery to
+/// a single connection; `None` keeps the original fan-out-to-all behaviour.
+#[derive(Clone)]
+pub struct RelayFrame {
+    pub target: Option<ConnectionId>,
+    pub text: String,
+}
+
 pub struct MasterChannel {
-    pub tx: broadcast::Sender<String>,
-    pub subscribers: usize,
+    pub tx: broadcast::Sender<RelayFrame>,
+    pub subscribers: HashSet<ConnectionId>,
 }
 
 pub struct SlaveChannel {
-    pub tx: broadcast::Sender<String>,
-    pub subscribers: usize,
+    pub tx: broadcast::Sender<RelayFrame>,
+    pub subscribers: HashSet<ConnectionId>,
+}
+
+/// Bounds for a per-device store-and-forward queue: at most `max_len` messages
+/// are retained, and anything older than `ttl` is discarded on access.
+#[derive(Clone, Copy)]
+pub struct QueueConfig {
+    pub max_len: usize,
+    pub ttl: Duration,
+}
+
+/// Bounded FIFO of messages waiting for an offline side to (re)attach.
+///
+/// Pushes past `max_len` evict the oldest entry, and expired entries are pruned
+/// lazily both on push and on drain, so a device that never comes back cannot
+/// grow the buffer without limit.
+pub struct OfflineQueue {
+    buf: VecDeque<(Instant, String)>,
+    config: QueueConfig,
+}
+
+impl OfflineQueue {
+    fn new(config: QueueConfig) -> Self {
+        Self { buf: VecDeque::new(), config }
+    }
+
+    fn prune(&mut self, now: Instant) {
+        while let Some((ts, _)) = self.buf.front() {
+            if now.duration_since(*ts) > self.config.ttl {
+                self.buf.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Append `text`, evicting the oldest entry if the buffer is full. Returns
+    /// `true` if the message was retained, or `false` when buffering is disabled
+    /// (`max_len == 0`) and the message was dropped.
+    pub(crate) fn push(&mut self, text: String) -> bool {
+        let now = Instant::now();
+        self.prune(now);
+        if self.config.max_len == 0 {
+            return false;
+        }
+        while self.buf.len() >= self.config.max_len {
+            self.buf.pop_front();
+        }
+        self.buf.push_back((now, text));
+        true
+    }
+
+    /// Remove and return all non-expired buffered messages in FIFO order.
+    fn drain(&mut self) -> Vec<String> {
+        self.prune(Instant::now());
+        self.buf.drain(..).map(|(_, text)| text).collect()
+    }
 }
 
-pub async fn subscribe_master(connections: &crate::Connections, device_id: &str) -> broadcast::Receiver<String> {
+/// State shared for a single `device_id`: the two side channels plus the
+/// store-and-forward queues that hold traffic for a side while it has no
+/// subscribers.
+pub struct ConnectionPair {
+    pub master: Option<MasterChannel>,
+    pub slave: Option<SlaveChannel>,
+    /// Messages destined for the master side while it is offline.
+    pub master_queue: OfflineQueue,
+    /// Messages destined for the slave side while it is offline.
+    pub slave_queue: OfflineQueue,
+    /// Remote nodes (by base URL) with a master-side subscriber, reference
+    /// counted. Only populated on the node that owns the `device_id`.
+    pub master_remotes: HashMap<String, usize>,
+    /// Remote nodes with a slave-side subscriber. Owner-only, as above.
+    pub slave_remotes: HashMap<String, usize>,
+}
+
+impl ConnectionPair {
+    pub(crate) fn new(config: QueueConfig) -> Self {
+        Self {
+            master: None,
+            slave: None,
+            master_queue: OfflineQueue::new(config),
+            slave_queue: OfflineQueue::new(config),
+            master_remotes: HashMap::new(),
+            slave_remotes: HashMap::new(),
+        }
+    }
+
+    /// Remote node URLs subscribed to the given side, used by an owner to fan a
+    /// frame out to the nodes hosting the counterpart's connections.
+    pub fn remotes(&self, to_master: bool) -> Vec<String> {
+        let map = if to_master { &self.master_remotes } else { &self.slave_remotes };
+        map.keys().cloned().collect()
+    }
+}
+
+/// Record that `node_url` has a subscriber on the given side of `device_id`.
+pub async fn add_remote_subscriber(
+    connections: &crate::Connections,
+    device_id: &str,
+    to_master: bool,
+    node_url: String,
+    config: QueueConfig,
+) {
+    let mut conn = connections.write().await;
+    let entry = conn.entry(device_id.to_string()).or_insert_with(|| ConnectionPair::new(config));
+    let map = if to_master { &mut entry.master_remotes } else { &mut entry.slave_remotes };
+    *map.entry(node_url).or_insert(0) += 1;
+}
+
+/// Drop a previously-registered remote subscription, cleaning up the entry when
+/// nothing else references it.
+pub async fn remove_remote_subscriber(
+    connections: &crate::Connections,
+    device_id: &str,
+    to_master: bool,
+    node_url: &str,
+) {
     let mut conn = connections.write().await;
-    let entry = conn.entry(device_id.to_string()).or_insert((None, None));
-    if let Some(chan) = &mut entry.0 {
-        chan.subscribers += 1;
+    if let Some(entry) = conn.get_mut(device_id) {
+        let map = if to_master { &mut entry.master_remotes } else { &mut entry.slave_remotes };
+        if let Some(count) = map.get_mut(node_url) {
+            *count = count.saturating_sub(1);
+            if *count == 0 {
+                map.remove(node_url);
+            }
+        }
+        if entry.master.is_none()
+            && entry.slave.is_none()
+            && entry.master_remotes.is_empty()
+            && entry.slave_remotes.is_empty()
+        {
+            conn.remove(device_id);
+        }
+    }
+}
+
+pub async fn subscribe_master(
+    connections: &crate::Connections,
+    device_id: &str,
+    conn_id: ConnectionId,
+    config: QueueConfig,
+) -> broadcast::Receiver<RelayFrame> {
+    let mut conn = connections.write().await;
+    let entry = conn.entry(device_id.to_string()).or_insert_with(|| ConnectionPair::new(config));
+    if let Some(chan) = &mut entry.master {
+        chan.subscribers.insert(conn_id);
         chan.tx.subscribe()
     } else {
         let (tx, _rx) = broadcast::channel(100);
-        entry.0 = Some(MasterChannel { tx: tx.clone(), subscribers: 1 });
+        entry.master = Some(MasterChannel { tx: tx.clone(), subscribers: HashSet::from([conn_id]) });
         tx.subscribe()
     }
 }
 
-pub async fn subscribe_slave(connections: &crate::Connections, device_id: &str) -> broadcast::Receiver<String> {
+pub async fn subscribe_slave(
+    connections: &crate::Connections,
+    device_id: &str,
+    conn_id: ConnectionId,
+    config: QueueConfig,
+) -> broadcast::Receiver<RelayFrame> {
     let mut conn = connections.write().await;
-    let entry = conn.entry(device_id.to_string()).or_insert((None, None));
-    if let Some(chan) = &mut entry.1 {
-        chan.subscribers += 1;
+    let entry = conn.entry(device_id.to_string()).or_insert_with(|| ConnectionPair::new(config));
+    if let Some(chan) = &mut entry.slave {
+        chan.subscribers.insert(conn_id);
         chan.tx.subscribe()
     } else {
         let (tx, _rx) = broadcast::channel(100);
-        entry.1 = Some(SlaveChannel { tx: tx.clone(), subscribers: 1 });
+        entry.slave = Some(SlaveChannel { tx: tx.clone(), subscribers: HashSet::from([conn_id]) });
         tx.subscribe()
     }
 }
 
+/// Drain any messages buffered for the side a freshly-subscribed client belongs
+/// to, so they can be replayed before live forwarding resumes.
+pub async fn drain_queue(connections: &crate::Connections, device_id: &str, is_master: bool) -> Vec<String> {
+    let mut conn = connections.write().await;
+    match conn.get_mut(device_id) {
+        Some(entry) if is_master => entry.master_queue.drain(),
+        Some(entry) => entry.slave_queue.drain(),
+        None => Vec::new(),
+    }
+}
+
 pub fn log_forward(device_id: &str, direction: &str, text: &str) {
     let pretty = match serde_json::from_str::<JsonValue>(text) {
         Ok(v) => serde_json::to_string(&v).unwrap_or_else(|_| text.to_string()),
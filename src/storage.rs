@@ -0,0 +1,94 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+
+/// Optional persistence layer: every relayed message is recorded so that a
+/// freshly paired client can replay what it missed via the `history` command.
+///
+/// A per-`device_id` monotonic sequence number lets a client page backwards
+/// with `before_seq`, CHATHISTORY-style.
+pub struct Storage {
+    pool: SqlitePool,
+}
+
+/// One persisted message, as returned by [`Storage::history`].
+#[derive(Serialize)]
+pub struct HistoryEntry {
+    pub seq: i64,
+    pub direction: String,
+    pub ts: i64,
+    pub payload: String,
+}
+
+impl Storage {
+    /// Open the SQLite database at `url` and create the `messages` table if it
+    /// does not exist. The database file itself is created only when the URL
+    /// asks for it (e.g. `?mode=rwc`).
+    pub async fn connect(url: &str) -> Result<Self, sqlx::Error> {
+        let pool = SqlitePoolOptions::new().max_connections(5).connect(url).await?;
+        sqlx::query(
+            "CREATE TABLE IF NOT EXISTS messages (
+                device_id TEXT NOT NULL,
+                seq       INTEGER NOT NULL,
+                direction TEXT NOT NULL,
+                ts        INTEGER NOT NULL,
+                payload   TEXT NOT NULL,
+                PRIMARY KEY (device_id, seq)
+            )",
+        )
+        .execute(&pool)
+        .await?;
+        Ok(Self { pool })
+    }
+
+    /// Append a relayed message for `device_id`, assigning it the next sequence
+    /// number for that device.
+    pub async fn record(&self, device_id: &str, direction: &str, payload: &str) -> Result<(), sqlx::Error> {
+        let ts = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() as i64)
+            .unwrap_or(0);
+        sqlx::query(
+            "INSERT INTO messages (device_id, seq, direction, ts, payload)
+             VALUES (?1,
+                     (SELECT COALESCE(MAX(seq), 0) + 1 FROM messages WHERE device_id = ?1),
+                     ?2, ?3, ?4)",
+        )
+        .bind(device_id)
+        .bind(direction)
+        .bind(ts)
+        .bind(payload)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    /// Return up to `limit` most-recent messages for `device_id`, oldest first.
+    /// When `before_seq` is set, only messages with a lower sequence number are
+    /// returned, allowing a client to page further back.
+    pub async fn history(
+        &self,
+        device_id: &str,
+        limit: i64,
+        before_seq: Option<i64>,
+    ) -> Result<Vec<HistoryEntry>, sqlx::Error> {
+        let rows = sqlx::query_as::<_, (i64, String, i64, String)>(
+            "SELECT seq, direction, ts, payload FROM messages
+             WHERE device_id = ?1 AND (?2 IS NULL OR seq < ?2)
+             ORDER BY seq DESC LIMIT ?3",
+        )
+        .bind(device_id)
+        .bind(before_seq)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut entries: Vec<HistoryEntry> = rows
+            .into_iter()
+            .map(|(seq, direction, ts, payload)| HistoryEntry { seq, direction, ts, payload })
+            .collect();
+        entries.reverse();
+        Ok(entries)
+    }
+}
@@ -1,10 +1,62 @@
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as JsonValue;
 
 #[derive(Deserialize)]
 pub struct ConnectParams {
     pub id: String,
 }
 
+/// Stable identifier assigned to each subscribed connection, used to address a
+/// single peer among several on the same side.
+pub type ConnectionId = u64;
+
+/// Optional wrapper a sender may use to request a delivery acknowledgement
+/// (`id`) and/or target a single peer connection (`to`). The relay forwards
+/// `payload` to the counterpart and, when `id` is set, reports back the
+/// [`MessageStatus`].
+#[derive(Deserialize)]
+pub struct Envelope {
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub to: Option<ConnectionId>,
+    pub payload: JsonValue,
+}
+
+/// Control command a master sends to replay persisted history for a device
+/// before live forwarding resumes.
+#[derive(Deserialize)]
+pub struct HistoryQuery {
+    pub device_id: String,
+    pub limit: Option<i64>,
+    pub before_seq: Option<i64>,
+}
+
+/// Outcome of a forwarding attempt, reported to the sender in a
+/// `{"type":"status","id":...,"status":...}` frame. Also carried between nodes
+/// so a relayed frame's true status propagates back to the origin.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MessageStatus {
+    /// The counterpart side had a live subscriber and the frame was handed off.
+    Delivered,
+    /// The counterpart side was offline and buffering was unavailable, so the
+    /// frame was dropped.
+    NoRecipient,
+    /// The counterpart side was offline and the frame was held for replay.
+    Buffered,
+}
+
+impl MessageStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MessageStatus::Delivered => "delivered",
+            MessageStatus::NoRecipient => "no_recipient",
+            MessageStatus::Buffered => "buffered",
+        }
+    }
+}
+
 pub enum ClientType {
     Master,
     Slave,
@@ -17,4 +69,29 @@ impl std::fmt::Display for ClientType {
             ClientType::Slave => write!(f, "Slave"),
         }
     }
-}
\ No newline at end of file
+}
+
+/// First control frame a client must send after the WebSocket upgrade, before
+/// it is subscribed to a channel. Carries the credentials the configured
+/// [`Auth`](crate::auth::Auth) verifier checks.
+#[derive(Deserialize)]
+pub struct InitMessage {
+    #[serde(rename = "type")]
+    pub msg_type: String,
+    pub device_id: String,
+    pub access_token: String,
+    pub role: Role,
+    /// Opt in to replay of messages that were buffered while this side was
+    /// offline. Defaults to `false` so clients that cannot handle a backlog are
+    /// unaffected.
+    #[serde(default)]
+    pub replay: bool,
+}
+
+/// Side of the pairing a client is claiming in its init handshake.
+#[derive(Deserialize, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Master,
+    Slave,
+}
@@ -0,0 +1,91 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio::time::{sleep, timeout, Duration};
+
+/// These tests share the process-global `AUTH_SHARED_SECRET`, so they live in
+/// their own binary and each spins up a server on a dedicated port.
+const SECRET: &str = "s3cret";
+
+fn init_frame(device_id: &str, role: &str, token: &str) -> Message {
+    Message::Text(format!(
+        "{{\"type\":\"init\",\"device_id\":\"{}\",\"access_token\":\"{}\",\"role\":\"{}\"}}",
+        device_id, token, role
+    ))
+}
+
+/// Read frames until a Close arrives (the server closes with code 1008 on a
+/// rejected handshake) or the stream ends, returning whether it closed.
+async fn expect_close<S>(stream: &mut S) -> bool
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        match timeout(Duration::from_secs(1), stream.next()).await {
+            Ok(Some(Ok(Message::Close(_)))) => return true,
+            Ok(Some(Ok(_))) => continue,
+            // A dropped connection after the close frame also counts as rejected.
+            Ok(Some(Err(_))) | Ok(None) => return true,
+            Err(_) => return false,
+        }
+    }
+}
+
+#[tokio::test]
+async fn rejects_wrong_token() {
+    std::env::set_var("AUTH_SHARED_SECRET", SECRET);
+    let bind = "0.0.0.0:4101".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let (ws, _) = connect_async("ws://127.0.0.1:4101/register?id=dev_auth").await.expect("connect");
+    let (mut tx, mut rx) = ws.split();
+    tx.send(init_frame("dev_auth", "slave", "wrong")).await.expect("init");
+
+    assert!(expect_close(&mut rx).await, "bad token should be rejected");
+}
+
+#[tokio::test]
+async fn rejects_wrong_role() {
+    std::env::set_var("AUTH_SHARED_SECRET", SECRET);
+    let bind = "0.0.0.0:4102".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    // Good token, but claims `master` on the slave endpoint.
+    let (ws, _) = connect_async("ws://127.0.0.1:4102/register?id=dev_auth").await.expect("connect");
+    let (mut tx, mut rx) = ws.split();
+    tx.send(init_frame("dev_auth", "master", SECRET)).await.expect("init");
+
+    assert!(expect_close(&mut rx).await, "role mismatch should be rejected");
+}
+
+#[tokio::test]
+async fn rejects_device_id_mismatch() {
+    std::env::set_var("AUTH_SHARED_SECRET", SECRET);
+    let bind = "0.0.0.0:4103".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    // Valid credentials for `mine`, but subscribing to `victim`'s channel.
+    let (ws, _) = connect_async("ws://127.0.0.1:4103/register?id=victim").await.expect("connect");
+    let (mut tx, mut rx) = ws.split();
+    tx.send(init_frame("mine", "slave", SECRET)).await.expect("init");
+
+    assert!(expect_close(&mut rx).await, "device id mismatch should be rejected");
+}
+
+#[tokio::test]
+async fn accepts_valid_handshake() {
+    std::env::set_var("AUTH_SHARED_SECRET", SECRET);
+    let bind = "0.0.0.0:4104".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let (ws, _) = connect_async("ws://127.0.0.1:4104/register?id=dev_auth").await.expect("connect");
+    let (mut tx, mut rx) = ws.split();
+    tx.send(init_frame("dev_auth", "slave", SECRET)).await.expect("init");
+
+    // A good handshake stays open: no frame should arrive promptly.
+    let res = timeout(Duration::from_millis(300), rx.next()).await;
+    assert!(res.is_err(), "valid handshake should not be closed");
+}
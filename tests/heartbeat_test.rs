@@ -0,0 +1,40 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio::time::{sleep, timeout, Duration};
+
+fn init_frame(device_id: &str, role: &str) -> Message {
+    Message::Text(format!(
+        "{{\"type\":\"init\",\"device_id\":\"{}\",\"access_token\":\"t\",\"role\":\"{}\"}}",
+        device_id, role
+    ))
+}
+
+/// A peer that never answers the server's keepalive Pings should be evicted once
+/// it goes idle past the heartbeat timeout. The test deliberately never polls the
+/// stream (so tungstenite cannot auto-Pong), then reads and expects the socket to
+/// have been closed from the server side.
+#[tokio::test]
+async fn idle_peer_is_evicted_after_timeout() {
+    std::env::set_var("HEARTBEAT_INTERVAL_SECS", "1");
+    std::env::set_var("HEARTBEAT_TIMEOUT_SECS", "1");
+    let bind = "0.0.0.0:4201".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let (ws, _) = connect_async("ws://127.0.0.1:4201/register?id=dev_hb").await.expect("connect");
+    let (mut tx, mut rx) = ws.split();
+    tx.send(init_frame("dev_hb", "slave")).await.expect("init");
+
+    // Stay silent (and unread) well past the 1s timeout so the server evicts us.
+    sleep(Duration::from_millis(2500)).await;
+
+    // The next read drains buffered Pings and then the server's Close/teardown.
+    let closed = loop {
+        match timeout(Duration::from_secs(2), rx.next()).await {
+            Ok(Some(Ok(Message::Close(_)))) | Ok(Some(Err(_))) | Ok(None) => break true,
+            Ok(Some(Ok(_))) => continue,
+            Err(_) => break false,
+        }
+    };
+    assert!(closed, "idle peer should have been evicted");
+}
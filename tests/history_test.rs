@@ -0,0 +1,103 @@
+use futures_util::{SinkExt, StreamExt};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+use tokio::time::{sleep, timeout, Duration};
+
+fn init_frame(device_id: &str, role: &str) -> Message {
+    Message::Text(format!(
+        "{{\"type\":\"init\",\"device_id\":\"{}\",\"access_token\":\"t\",\"role\":\"{}\"}}",
+        device_id, role
+    ))
+}
+
+/// Read frames from `stream` until one containing `needle` arrives.
+async fn read_until<S>(stream: &mut S, needle: &str) -> String
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        let m = timeout(Duration::from_secs(1), stream.next())
+            .await
+            .expect("timeout")
+            .expect("closed")
+            .expect("error");
+        if let Message::Text(txt) = m {
+            if txt.contains(needle) {
+                return txt;
+            }
+        }
+    }
+}
+
+/// With persistence enabled a relayed message is recorded and can be replayed to
+/// a master via the `history` control command.
+#[tokio::test]
+async fn history_command_replays_persisted_messages() {
+    std::env::set_var("HISTORY_DB", "sqlite:/tmp/ovs_history_roundtrip.db?mode=rwc");
+    let _ = std::fs::remove_file("/tmp/ovs_history_roundtrip.db");
+
+    let bind = "0.0.0.0:4202".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(300)).await;
+
+    let device_id = "dev_hist";
+
+    let (s_ws, _) = connect_async(&format!("ws://127.0.0.1:4202/register?id={}", device_id)).await.expect("slave connect");
+    let (mut s_tx, _s_rx) = s_ws.split();
+    s_tx.send(init_frame(device_id, "slave")).await.expect("slave init");
+
+    let (m_ws, _) = connect_async(&format!("ws://127.0.0.1:4202/pair?id={}", device_id)).await.expect("master connect");
+    let (mut m_tx, mut m_rx) = m_ws.split();
+    m_tx.send(init_frame(device_id, "master")).await.expect("master init");
+    sleep(Duration::from_millis(100)).await;
+
+    // Master -> slave message is relayed and persisted.
+    m_tx.send(Message::Text("remembered".to_string())).await.expect("send");
+    sleep(Duration::from_millis(200)).await;
+
+    // Ask for the device's history back.
+    m_tx.send(Message::Text(format!("{{\"type\":\"history\",\"device_id\":\"{}\",\"limit\":10}}", device_id)))
+        .await
+        .expect("history request");
+
+    let batch = read_until(&mut m_rx, "\"type\":\"history\"").await;
+    assert!(batch.contains("\"count\":1"), "expected one recorded message, got {batch}");
+    assert!(batch.contains("\"payload\":\"remembered\""), "expected persisted payload, got {batch}");
+}
+
+/// The history read is scoped to the connection's authenticated device: a master
+/// cannot read another device's history by naming it in the query.
+#[tokio::test]
+async fn history_command_cannot_read_another_device() {
+    std::env::set_var("HISTORY_DB", "sqlite:/tmp/ovs_history_scope.db?mode=rwc");
+    let _ = std::fs::remove_file("/tmp/ovs_history_scope.db");
+
+    let bind = "0.0.0.0:4203".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(300)).await;
+
+    // Populate victim's history via its own pairing.
+    let (vs_ws, _) = connect_async("ws://127.0.0.1:4203/register?id=victim").await.expect("victim slave");
+    let (mut vs_tx, _vs_rx) = vs_ws.split();
+    vs_tx.send(init_frame("victim", "slave")).await.expect("victim slave init");
+    let (vm_ws, _) = connect_async("ws://127.0.0.1:4203/pair?id=victim").await.expect("victim master");
+    let (mut vm_tx, _vm_rx) = vm_ws.split();
+    vm_tx.send(init_frame("victim", "master")).await.expect("victim master init");
+    sleep(Duration::from_millis(100)).await;
+    vm_tx.send(Message::Text("secret".to_string())).await.expect("send secret");
+    sleep(Duration::from_millis(200)).await;
+
+    // Attacker connects for its own device and asks for the victim's history.
+    let (a_ws, _) = connect_async("ws://127.0.0.1:4203/pair?id=attacker").await.expect("attacker");
+    let (mut a_tx, mut a_rx) = a_ws.split();
+    a_tx.send(init_frame("attacker", "master")).await.expect("attacker init");
+    a_tx.send(Message::Text("{\"type\":\"history\",\"device_id\":\"victim\",\"limit\":10}".to_string()))
+        .await
+        .expect("history request");
+
+    // The read is scoped to "attacker", which has no history, so the batch is
+    // empty and never leaks the victim's payload.
+    let batch = read_until(&mut a_rx, "\"type\":\"history\"").await;
+    assert!(batch.contains("\"count\":0"), "expected empty history, got {batch}");
+    assert!(!batch.contains("secret"), "must not leak another device's history: {batch}");
+    assert!(batch.contains("\"device_id\":\"attacker\""), "batch should be scoped to attacker, got {batch}");
+}
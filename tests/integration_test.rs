@@ -2,6 +2,49 @@ use futures_util::{SinkExt, StreamExt};
 use tokio_tungstenite::{connect_async, tungstenite::Message};
 use tokio::time::{sleep, timeout, Duration};
 
+/// Build the init control frame a client must send after connecting. The
+/// default (no-auth) verifier accepts any `access_token`.
+fn init_frame(device_id: &str, role: &str) -> Message {
+    Message::Text(format!(
+        "{{\"type\":\"init\",\"device_id\":\"{}\",\"access_token\":\"t\",\"role\":\"{}\"}}",
+        device_id, role
+    ))
+}
+
+/// Like [`init_frame`] but opting into replay of buffered messages.
+fn init_frame_replay(device_id: &str, role: &str) -> Message {
+    Message::Text(format!(
+        "{{\"type\":\"init\",\"device_id\":\"{}\",\"access_token\":\"t\",\"role\":\"{}\",\"replay\":true}}",
+        device_id, role
+    ))
+}
+
+/// Peer join/leave notices share the stream with relayed payloads; tests that
+/// assert on forwarded data skip them.
+fn is_control(text: &str) -> bool {
+    text.contains("\"peer_connected\"") || text.contains("\"peer_removed\"")
+}
+
+/// Read the next text frame from a split stream, skipping peer notices.
+async fn next_text<S>(stream: &mut S) -> String
+where
+    S: StreamExt<Item = Result<Message, tokio_tungstenite::tungstenite::Error>> + Unpin,
+{
+    loop {
+        let m = timeout(Duration::from_secs(1), stream.next())
+            .await
+            .expect("timeout waiting for frame")
+            .expect("stream closed")
+            .expect("stream error");
+        if let Message::Text(txt) = m {
+            if is_control(&txt) {
+                continue;
+            }
+            return txt;
+        }
+    }
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
 async fn device_broadcasts_to_multiple_masters() {
     let port = 4001u16;
@@ -15,18 +58,24 @@ async fn device_broadcasts_to_multiple_masters() {
     // slave connects to /register
     let (device_ws_stream, _) = connect_async(&format!("ws://127.0.0.1:{}/register?id={}", port, "device123")).await.expect("device connect");
     let (mut device_sink, mut device_stream) = device_ws_stream.split();
+    device_sink.send(init_frame("device123", "slave")).await.expect("device init");
 
     // masters connect to /pair
     let mut mobile_sinks = Vec::new();
     let mut mobile_receivers = Vec::new();
     for _ in 0..3 {
         let (ws_stream, _) = connect_async(&format!("ws://127.0.0.1:{}/pair?id={}", port, "device123")).await.expect("mobile connect");
-        let (s, mut r) = ws_stream.split();
+        let (mut s, mut r) = ws_stream.split();
+        s.send(init_frame("device123", "master")).await.expect("mobile init");
 
         let (tx, rx) = tokio::sync::mpsc::channel::<String>(1);
         tokio::spawn(async move {
-            if let Some(Ok(Message::Text(txt))) = r.next().await {
+            while let Some(Ok(Message::Text(txt))) = r.next().await {
+                if is_control(&txt) {
+                    continue;
+                }
                 let _ = tx.send(txt).await;
+                break;
             }
         });
 
@@ -37,11 +86,18 @@ async fn device_broadcasts_to_multiple_masters() {
     // Device reader
     let (dev_tx, mut dev_rx) = tokio::sync::mpsc::channel::<String>(1);
     tokio::spawn(async move {
-        if let Some(Ok(Message::Text(txt))) = device_stream.next().await {
+        while let Some(Ok(Message::Text(txt))) = device_stream.next().await {
+            if is_control(&txt) {
+                continue;
+            }
             let _ = dev_tx.send(txt).await;
+            break;
         }
     });
 
+    // give the masters a moment to finish subscribing
+    sleep(Duration::from_millis(100)).await;
+
     // Device broadcasts to all mobiles
     let msg = "hello mobiles".to_string();
     device_sink.send(Message::Text(msg.clone())).await.expect("send from device");
@@ -75,29 +131,45 @@ async fn test_device_mobile_communication() {
         .await
         .expect("Failed to connect device");
     let (mut device_tx, mut device_rx) = device_ws.split();
+    device_tx.send(init_frame(device_id, "slave")).await.expect("device init");
 
     // master connects to /pair
     let (mobile_ws, _) = connect_async(&format!("ws://127.0.0.1:3001/pair?id={}", device_id))
         .await
         .expect("Failed to connect mobile");
     let (mut mobile_tx, mut mobile_rx) = mobile_ws.split();
+    mobile_tx.send(init_frame(device_id, "master")).await.expect("mobile init");
+
+    sleep(Duration::from_millis(100)).await;
 
     // Mobile sends command to device
     mobile_tx.send(Message::Text("Hello from mobile".to_string())).await.unwrap();
-    
-    let msg = timeout(Duration::from_secs(1), device_rx.next()).await
-        .expect("Timeout waiting for device message")
-        .expect("Device connection closed")
-        .expect("Device message error");
+
+    let msg = loop {
+        let m = timeout(Duration::from_secs(1), device_rx.next()).await
+            .expect("Timeout waiting for device message")
+            .expect("Device connection closed")
+            .expect("Device message error");
+        if matches!(&m, Message::Text(t) if is_control(t)) {
+            continue;
+        }
+        break m;
+    };
     assert_eq!(msg, Message::Text("Hello from mobile".to_string()));
 
     // Device responds to mobile
     device_tx.send(Message::Text("Hello from device".to_string())).await.unwrap();
-    
-    let msg = timeout(Duration::from_secs(1), mobile_rx.next()).await
-        .expect("Timeout waiting for mobile message")
-        .expect("Mobile connection closed")
-        .expect("Mobile message error");
+
+    let msg = loop {
+        let m = timeout(Duration::from_secs(1), mobile_rx.next()).await
+            .expect("Timeout waiting for mobile message")
+            .expect("Mobile connection closed")
+            .expect("Mobile message error");
+        if matches!(&m, Message::Text(t) if is_control(t)) {
+            continue;
+        }
+        break m;
+    };
     assert_eq!(msg, Message::Text("Hello from device".to_string()));
 
     server_handle.abort();
@@ -116,14 +188,17 @@ async fn mobile_messages_do_not_go_to_other_masters() {
     let (device_ws, _) = connect_async(&format!("ws://127.0.0.1:4002/register?id={}", device_id))
         .await
         .expect("Device connect");
-    let (mut _device_tx, mut _device_rx) = device_ws.split();
+    let (mut device_tx, mut _device_rx) = device_ws.split();
+    device_tx.send(init_frame(device_id, "slave")).await.expect("device init");
 
     // masters connect to /pair
     let (m1_ws, _) = connect_async(&format!("ws://127.0.0.1:4002/pair?id={}", device_id)).await.expect("m1 connect");
     let (mut m1_tx, _m1_rx) = m1_ws.split();
+    m1_tx.send(init_frame(device_id, "master")).await.expect("m1 init");
 
     let (m2_ws, _) = connect_async(&format!("ws://127.0.0.1:4002/pair?id={}", device_id)).await.expect("m2 connect");
-    let (_m2_tx, mut m2_rx) = m2_ws.split();
+    let (mut m2_tx, mut m2_rx) = m2_ws.split();
+    m2_tx.send(init_frame(device_id, "master")).await.expect("m2 init");
 
     // Spawn reader for master2 that attempts to read a message (should NOT get messages from master1)
     let (m2_chan_tx, mut m2_chan_rx) = tokio::sync::mpsc::channel::<String>(1);
@@ -133,6 +208,8 @@ async fn mobile_messages_do_not_go_to_other_masters() {
         }
     });
 
+    sleep(Duration::from_millis(100)).await;
+
     // master1 sends command to device
     let text = "from mobile1".to_string();
     m1_tx.send(Message::Text(text.clone())).await.expect("send from m1");
@@ -140,4 +217,115 @@ async fn mobile_messages_do_not_go_to_other_masters() {
     // master2 should NOT receive mobile1's message (we expect a timeout)
     let res = timeout(Duration::from_millis(200), m2_chan_rx.recv()).await;
     assert!(res.is_err(), "mobile2 should NOT receive messages from another mobile");
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn offline_queue_buffers_and_replays_with_count() {
+    let bind = "0.0.0.0:4003".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let device_id = "dev_queue";
+
+    // Master connects while the slave is offline and sends two commands.
+    let (m_ws, _) = connect_async(&format!("ws://127.0.0.1:4003/pair?id={}", device_id)).await.expect("master connect");
+    let (mut m_tx, _m_rx) = m_ws.split();
+    m_tx.send(init_frame(device_id, "master")).await.expect("master init");
+    sleep(Duration::from_millis(100)).await;
+    m_tx.send(Message::Text("msg-1".to_string())).await.expect("send msg-1");
+    m_tx.send(Message::Text("msg-2".to_string())).await.expect("send msg-2");
+    sleep(Duration::from_millis(100)).await;
+
+    // Slave reconnects opting into replay and should receive the count notice
+    // followed by the buffered messages in order.
+    let (s_ws, _) = connect_async(&format!("ws://127.0.0.1:4003/register?id={}", device_id)).await.expect("slave connect");
+    let (mut s_tx, mut s_rx) = s_ws.split();
+    s_tx.send(init_frame_replay(device_id, "slave")).await.expect("slave init");
+
+    let notice = next_text(&mut s_rx).await;
+    assert!(notice.contains("\"type\":\"replay\""), "expected replay notice, got {notice}");
+    assert!(notice.contains("\"count\":2"), "expected count 2, got {notice}");
+    assert_eq!(next_text(&mut s_rx).await, "msg-1");
+    assert_eq!(next_text(&mut s_rx).await, "msg-2");
+}
+
+#[tokio::test]
+async fn wrapped_payload_gets_delivery_ack() {
+    let bind = "0.0.0.0:4004".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let device_id = "dev_ack";
+
+    let (s_ws, _) = connect_async(&format!("ws://127.0.0.1:4004/register?id={}", device_id)).await.expect("slave connect");
+    let (mut s_tx, mut s_rx) = s_ws.split();
+    s_tx.send(init_frame(device_id, "slave")).await.expect("slave init");
+
+    let (m_ws, _) = connect_async(&format!("ws://127.0.0.1:4004/pair?id={}", device_id)).await.expect("master connect");
+    let (mut m_tx, mut m_rx) = m_ws.split();
+    m_tx.send(init_frame(device_id, "master")).await.expect("master init");
+    sleep(Duration::from_millis(100)).await;
+
+    // Master wraps the payload to request an acknowledgement.
+    m_tx.send(Message::Text("{\"id\":\"abc\",\"payload\":\"hi\"}".to_string())).await.expect("send wrapped");
+
+    // Slave receives the unwrapped payload...
+    assert_eq!(next_text(&mut s_rx).await, "\"hi\"");
+
+    // ...and the master gets a delivered ack for the message id.
+    let ack = next_text(&mut m_rx).await;
+    assert!(ack.contains("\"type\":\"status\""), "expected status frame, got {ack}");
+    assert!(ack.contains("\"id\":\"abc\""), "expected id abc, got {ack}");
+    assert!(ack.contains("\"status\":\"delivered\""), "expected delivered, got {ack}");
+}
+
+#[tokio::test]
+async fn targeted_frame_reaches_only_the_addressed_master() {
+    let bind = "0.0.0.0:4005".to_string();
+    let _server = tokio::spawn(async move { openvibe_server::run_server_on(&bind).await });
+    sleep(Duration::from_millis(200)).await;
+
+    let device_id = "dev_target";
+
+    // Slave connects first so it learns the masters' connection ids via the
+    // peer_connected notices announced as each master joins.
+    let (s_ws, _) = connect_async(&format!("ws://127.0.0.1:4005/register?id={}", device_id)).await.expect("slave connect");
+    let (mut s_tx, mut s_rx) = s_ws.split();
+    s_tx.send(init_frame(device_id, "slave")).await.expect("slave init");
+
+    let (a_ws, _) = connect_async(&format!("ws://127.0.0.1:4005/pair?id={}", device_id)).await.expect("master A connect");
+    let (mut a_tx, mut a_rx) = a_ws.split();
+    a_tx.send(init_frame(device_id, "master")).await.expect("A init");
+
+    let (b_ws, _) = connect_async(&format!("ws://127.0.0.1:4005/pair?id={}", device_id)).await.expect("master B connect");
+    let (mut b_tx, mut b_rx) = b_ws.split();
+    b_tx.send(init_frame(device_id, "master")).await.expect("B init");
+    sleep(Duration::from_millis(100)).await;
+
+    // The first peer_connected notice is master A's connection id.
+    let notice = loop {
+        let m = timeout(Duration::from_secs(1), s_rx.next()).await
+            .expect("timeout")
+            .expect("closed")
+            .expect("error");
+        if let Message::Text(t) = m {
+            if t.contains("peer_connected") {
+                break t;
+            }
+        }
+    };
+    let a_id: u64 = notice
+        .split("\"conn_id\":")
+        .nth(1)
+        .and_then(|rest| rest.trim_end_matches('}').trim().parse().ok())
+        .expect("parse conn_id");
+
+    // Slave targets master A only.
+    s_tx.send(Message::Text(format!("{{\"to\":{},\"payload\":\"ping\"}}", a_id))).await.expect("send targeted");
+
+    assert_eq!(next_text(&mut a_rx).await, "\"ping\"");
+
+    // Master B must not receive the targeted frame.
+    let res = timeout(Duration::from_millis(200), b_rx.next()).await;
+    assert!(res.is_err(), "master B should not receive a frame targeted at A");
+}